@@ -70,3 +70,220 @@ impl<T> server::Clock<T> for Simple<T>
     ) {}
     fn end_cycles(self: &mut Self) {}
 }
+
+// scales a real duration by `rate`, clamping negative results to zero
+// (we don't support running time backwards)
+fn scale_duration(d: time::Duration, rate: f64) -> time::Duration {
+    let secs = d.as_secs() as f64 + d.subsec_nanos() as f64 * 1e-9;
+    let scaled = secs * rate;
+    if scaled <= 0.0 {
+        time::Duration::new(0, 0)
+    } else {
+        time::Duration::new(
+            scaled as u64,
+            (scaled.fract() * 1e9) as u32,
+        )
+    }
+}
+
+/// Like [Simple](struct.Simple.html), but with a mutable `rate` that scales
+/// how quickly in-game time passes relative to real time: 1.0 is normal
+/// speed, 2.0 is double speed, 0.0 pauses the clock outright.
+///
+/// Changing the rate re-anchors `last_time`/`start_instant` to the instant
+/// of the change, so time elapsed under the old rate is folded into
+/// `last_time` exactly once, rather than being lost or double-counted.
+#[derive(Clone)]
+pub struct Scaled<T=Time> {
+    start_instant: Option<time::Instant>,
+    last_time: T,
+    rate: f64,
+}
+
+impl<T> Scaled<T>
+    where T: Clone + ops::Sub + ops::Add<Diff<T>, Output=T>,
+          Diff<T>: From<time::Duration>,
+{
+    pub fn new(start_time: T) -> Self {
+        Scaled {
+            start_instant: None,
+            last_time: start_time,
+            rate: 1.0,
+        }
+    }
+
+    fn elapsed_as_of(self: &Self, now: time::Instant) -> time::Duration {
+        if let Some(start) = self.start_instant {
+            now.duration_since(start)
+        } else {
+            // time only passes if the clock has started
+            time::Duration::new(0, 0)
+        }
+    }
+
+    pub fn time(self: &Self, now: time::Instant) -> T {
+        let elapsed = scale_duration(self.elapsed_as_of(now), self.rate);
+        self.last_time.clone() + elapsed.into()
+    }
+
+    pub fn stop(self: &mut Self, now: time::Instant) {
+        self.last_time = self.time(now);
+        self.start_instant = None;
+    }
+
+    pub fn start(self: &mut Self, now: time::Instant) {
+        self.stop(now);
+        self.start_instant = Some(now);
+    }
+
+    pub fn rate(self: &Self) -> f64 {
+        self.rate
+    }
+
+    /// changes the playback rate, re-anchoring so time already elapsed
+    /// under the old rate isn't lost or double-counted when the new rate
+    /// takes over
+    pub fn set_rate(self: &mut Self, now: time::Instant, rate: f64) {
+        if self.start_instant.is_some() {
+            self.last_time = self.time(now);
+            self.start_instant = Some(now);
+        }
+        self.rate = rate;
+    }
+}
+
+impl<T> server::Clock<T> for Scaled<T>
+    where T: Clone + Ord + ops::Sub + ops::Add<Diff<T>, Output=T>,
+          time::Duration: From<Diff<T>>,
+          Diff<T>: From<time::Duration>,
+{
+    fn in_game(self: &mut Self, now: time::Instant) -> T {
+        self.time(now)
+    }
+    fn minimum_wait(
+        self: &mut Self,
+        now: T,
+        until: T,
+    ) -> time::Duration {
+        let gap: time::Duration = (until - now).into();
+        if self.rate <= 0.0 {
+            // paused: nothing will make the in-game gap close, so idle
+            // as long as the server is willing to, rather than spinning
+            time::Duration::from_secs(60 * 60 * 24)
+        } else {
+            scale_duration(gap, 1.0 / self.rate)
+        }
+    }
+    fn finished_cycle(
+        self: &mut Self,
+        _now: time::Instant,
+        _in_game: T,
+    ) {}
+    fn end_cycles(self: &mut Self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops;
+    use std::time;
+
+    use super::Scaled;
+
+    #[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+    struct TestTime(f64);
+
+    impl ops::Sub for TestTime {
+        type Output = TestTime;
+        fn sub(self: TestTime, other: TestTime) -> TestTime {
+            TestTime(self.0 - other.0)
+        }
+    }
+
+    impl ops::Add for TestTime {
+        type Output = TestTime;
+        fn add(self: TestTime, other: TestTime) -> TestTime {
+            TestTime(self.0 + other.0)
+        }
+    }
+
+    impl From<time::Duration> for TestTime {
+        fn from(d: time::Duration) -> TestTime {
+            TestTime(d.as_secs() as f64 + d.subsec_nanos() as f64 * 1e-9)
+        }
+    }
+
+    #[test]
+    fn set_rate_preserves_elapsed_time_across_a_rate_change() {
+        let start = time::Instant::now();
+        let mut clock: Scaled<TestTime> = Scaled::new(TestTime(0.0));
+        clock.start(start);
+
+        // 2 real seconds at the default 1x rate
+        let after_first_leg = start + time::Duration::from_secs(2);
+        clock.set_rate(after_first_leg, 2.0);
+
+        // 3 more real seconds, now at 2x
+        let after_second_leg = after_first_leg + time::Duration::from_secs(3);
+        let in_game = clock.time(after_second_leg);
+
+        // 2s @ 1x + 3s @ 2x = 2 + 6 = 8 in-game seconds: the elapsed time
+        // from the first leg must be folded into last_time exactly once,
+        // neither lost nor double-counted once the new rate applies
+        assert!((in_game.0 - 8.0).abs() < 1e-6, "{:?}", in_game);
+    }
+}
+
+/// A [Clock](../server/trait.Clock.html) whose time only moves when a test
+/// explicitly tells it to, via [advance](#method.advance) or
+/// [advance_to](#method.advance_to).
+///
+/// Pair this with [Server::run_until_idle](../server/struct.Server.html#method.run_until_idle)
+/// to drive an event system deterministically: push inputs onto the
+/// server's external channel, advance the clock by a fixed amount, and
+/// assert on the resulting state, with no dependence on wall-clock timing.
+#[derive(Clone)]
+pub struct MockClock<T> {
+    time: T,
+}
+
+impl<T: Clone> MockClock<T> {
+    pub fn new(start_time: T) -> Self {
+        MockClock { time: start_time }
+    }
+
+    pub fn now(self: &Self) -> T {
+        self.time.clone()
+    }
+
+    pub fn advance(self: &mut Self, delta: Diff<T>)
+        where T: ops::Sub + ops::Add<Diff<T>, Output=T>,
+    {
+        self.time = self.time.clone() + delta;
+    }
+
+    pub fn advance_to(self: &mut Self, time: T) {
+        self.time = time;
+    }
+}
+
+impl<T> server::Clock<T> for MockClock<T>
+    where T: Clone + Ord + ops::Sub,
+          time::Duration: From<Diff<T>>,
+{
+    fn in_game(self: &mut Self, _now: time::Instant) -> T {
+        self.time.clone()
+    }
+    fn minimum_wait(
+        self: &mut Self,
+        now: T,
+        until: T,
+    ) -> time::Duration {
+        (until - now).into()
+    }
+    fn finished_cycle(
+        self: &mut Self,
+        _now: time::Instant,
+        _in_game: T,
+    ) {}
+    fn end_cycles(self: &mut Self) {}
+}
@@ -0,0 +1,116 @@
+use std::any::Any;
+use std::ops;
+
+use rand;
+
+use entity_heap::{EntityHeap, UID};
+use event_queue::{EventQueue, Timeout};
+
+/// The capability to schedule and cancel future events.
+///
+/// Where [Event](../event_queue/trait.Event.html)/[GeneralEvent](../event_queue/trait.GeneralEvent.html)
+/// hard-code the whole game type `G`, code written against `TimerContext`
+/// only needs a type that can schedule events of type `E` at times `T` -
+/// handy for subsystems that should be testable against a minimal fake,
+/// or split out of the main game type entirely.
+///
+/// Any game that is `AsMut<EventQueue<E, T>>` gets this for free.
+///
+/// Unlike [Simulation](../event_queue/trait.Simulation.html), this is
+/// specific to [EventQueue](../event_queue/struct.EventQueue.html) rather
+/// than any `AsMut<Q: EventSource<E, T>>` backend: cancellation here is
+/// `EventQueue`'s slab-backed [Timeout](../event_queue/struct.Timeout.html),
+/// which [WheelEventQueue](../timing_wheel/struct.WheelEventQueue.html)
+/// doesn't (yet) implement. A game built on `WheelEventQueue` gets
+/// `Simulation` but not `TimerContext` until that catches up.
+pub trait TimerContext<E, T>
+    where T: Ord + Clone,
+{
+    fn now(self: &mut Self) -> T;
+
+    fn enqueue_absolute<Es>(self: &mut Self, event: Es, execute_time: T)
+        -> Timeout<T>
+        where Es: Into<E>;
+
+    fn enqueue_relative<Es, D>(self: &mut Self, event: Es, execute_delay: D)
+        -> Timeout<T>
+        where Es: Into<E>,
+              T: ops::Add<D, Output=T>;
+
+    fn cancel(self: &mut Self, timeout: Timeout<T>) -> bool;
+}
+
+impl<G, E, T> TimerContext<E, T> for G
+    where G: AsMut<EventQueue<E, T>>,
+          T: Ord + Clone,
+{
+    fn now(self: &mut Self) -> T {
+        self.as_mut().now()
+    }
+
+    fn enqueue_absolute<Es>(self: &mut Self, event: Es, execute_time: T)
+        -> Timeout<T>
+        where Es: Into<E>,
+    {
+        self.as_mut().enqueue_absolute(event, execute_time)
+    }
+
+    fn enqueue_relative<Es, D>(self: &mut Self, event: Es, execute_delay: D)
+        -> Timeout<T>
+        where Es: Into<E>,
+              T: ops::Add<D, Output=T>,
+    {
+        self.as_mut().enqueue_relative(event, execute_delay)
+    }
+
+    fn cancel(self: &mut Self, timeout: Timeout<T>) -> bool {
+        self.as_mut().cancel(timeout)
+    }
+}
+
+/// The capability to add, look up, and remove entities.
+///
+/// Any game that is `AsMut<EntityHeap>` gets this for free.
+pub trait EntityContext {
+    fn add<V: Any>(self: &mut Self, v: V) -> UID;
+    fn get<V: Any>(self: &mut Self, k: UID) -> Option<&V>;
+    fn get_mut<V: Any>(self: &mut Self, k: UID) -> Option<&mut V>;
+    fn remove<V: Any>(self: &mut Self, k: UID) -> Option<V>;
+}
+
+impl<G> EntityContext for G
+    where G: AsMut<EntityHeap>,
+{
+    fn add<V: Any>(self: &mut Self, v: V) -> UID {
+        self.as_mut().add(v)
+    }
+
+    fn get<V: Any>(self: &mut Self, k: UID) -> Option<&V> {
+        self.as_mut().get(k)
+    }
+
+    fn get_mut<V: Any>(self: &mut Self, k: UID) -> Option<&mut V> {
+        self.as_mut().get_mut(k)
+    }
+
+    fn remove<V: Any>(self: &mut Self, k: UID) -> Option<V> {
+        self.as_mut().remove(k)
+    }
+}
+
+/// The capability to draw random numbers.
+///
+/// Any game that is `AsMut<rand::XorShiftRng>` gets this for free, using
+/// the same generator type as [EntityHeap](../entity_heap/struct.EntityHeap.html)'s
+/// own UID generation.
+pub trait RngContext {
+    fn rng(self: &mut Self) -> &mut rand::XorShiftRng;
+}
+
+impl<G> RngContext for G
+    where G: AsMut<rand::XorShiftRng>,
+{
+    fn rng(self: &mut Self) -> &mut rand::XorShiftRng {
+        self.as_mut()
+    }
+}
@@ -1,5 +1,10 @@
+use std::cell::Cell;
 use std::collections;
+use std::marker;
 use std::ops;
+use std::rc::Rc;
+
+type Diff<T> = <T as ops::Sub>::Output;
 
 /// The general event trait.
 ///
@@ -64,12 +69,122 @@ impl<G> GeneralEvent<G> for EventBox<G> {
     }
 }
 
+/// A backend that an [EventQueue](struct.EventQueue.html)-like structure
+/// must provide in order to drive a [Simulation](trait.Simulation.html).
+///
+/// This is the seam that lets a game swap its scheduling backend (e.g. the
+/// plain `BTreeMap` based [EventQueue](struct.EventQueue.html), or a hashed
+/// timing wheel) without touching the `Simulation` blanket impl: anything
+/// that is `AsMut<Q>` for some `Q: EventSource<E, T>` gets `Simulation` for
+/// free.
+pub trait EventSource<E, T>
+    where T: Ord + Clone,
+{
+    fn now(self: &Self) -> T;
+    fn soonest(self: &Self) -> Option<T>;
+    fn is_empty(self: &Self) -> bool;
+    fn has_event_by(self: &Self, time: &T) -> bool {
+        if let Some(next_time) = self.soonest() {
+            next_time <= *time
+        } else {
+            false
+        }
+    }
+    /// removes and returns every event scheduled for the soonest time,
+    /// advancing `now` to that time if it is not already there
+    fn take_soonest(self: &mut Self) -> Vec<E>;
+    /// advances `now` directly, without invoking anything
+    /// (used when skipping ahead to a time with no due events)
+    fn set_now(self: &mut Self, time: T);
+}
+
+// a per-time slab of events: slots are reused once freed. The generation
+// stamped on each occupant comes from the enclosing `EventQueue`'s single
+// counter (not reset per-`Bucket`), since a `Bucket` is dropped from the
+// map entirely once it empties out - a fresh `Bucket` created later for
+// the same time would otherwise restart its own counter at 0 and collide
+// with a stale `Timeout` from the dead one (an ABA bug)
+struct Bucket<E> {
+    slots: Vec<Option<(u64, E)>>,
+    free: Vec<usize>,
+    occupied: usize,
+}
+
+impl<E> Bucket<E> {
+    fn new() -> Self {
+        Bucket {
+            slots: Vec::new(),
+            free: Vec::new(),
+            occupied: 0,
+        }
+    }
+
+    fn insert(self: &mut Self, generation: u64, event: E) -> usize {
+        self.occupied += 1;
+        match self.free.pop() {
+            Some(slot) => {
+                self.slots[slot] = Some((generation, event));
+                slot
+            }
+            None => {
+                self.slots.push(Some((generation, event)));
+                self.slots.len() - 1
+            }
+        }
+    }
+
+    fn slot_generation(self: &Self, slot: usize) -> Option<u64> {
+        self.slots.get(slot)
+            .and_then(|entry| entry.as_ref())
+            .map(|&(generation, _)| generation)
+    }
+
+    fn cancel(self: &mut Self, slot: usize, generation: u64) -> bool {
+        let matches = self.slot_generation(slot) == Some(generation);
+        if matches {
+            self.slots[slot] = None;
+            self.free.push(slot);
+            self.occupied -= 1;
+        }
+        matches
+    }
+
+    fn is_empty(self: &Self) -> bool {
+        self.occupied == 0
+    }
+
+    fn into_events(self: Self) -> Vec<E> {
+        self.slots
+            .into_iter()
+            .flat_map(|x| x)
+            .map(|(_, event)| event)
+            .collect()
+    }
+}
+
+/// an opaque handle to an event scheduled via `enqueue_absolute` or
+/// `enqueue_relative`, replacing the old fragile `(execute_time, index)`
+/// cancellation pair.
+///
+/// the slot a `Timeout` names is backed by a per-time [Bucket], so once
+/// that event has fired or been cancelled the slot may be reused by a
+/// later event; the generation recorded here ensures `cancel` can only
+/// ever remove the event this handle was actually issued for.
+pub struct Timeout<T> {
+    time: T,
+    slot: usize,
+    generation: u64,
+}
+
 pub struct EventQueue<E, T>
     where T: Ord + Clone
 {
     now: T,
-    // TODO small vec
-    events: collections::BTreeMap<T, Vec<Option<E>>>,
+    events: collections::BTreeMap<T, Bucket<E>>,
+    // a single counter shared by every `Bucket`, so `Timeout` generations
+    // stay globally unique even across a `Bucket` being dropped and a
+    // fresh one later created for the same time
+    next_generation: u64,
 }
 
 pub type PolyEventQueue<G, T> = EventQueue<EventBox<G>, T>;
@@ -81,6 +196,7 @@ impl<E, T> EventQueue<E, T>
         EventQueue {
             now: initial_time,
             events: collections::BTreeMap::new(),
+            next_generation: 0,
         }
     }
 
@@ -109,24 +225,25 @@ impl<E, T> EventQueue<E, T>
     }
 
     pub fn enqueue_absolute<Es>(self: &mut Self, event: Es, execute_time: T)
-        -> usize
+        -> Timeout<T>
         where Es: Into<E>
     {
         let call_back = event.into();
-        let events = self
+        let generation = self.next_generation;
+        self.next_generation += 1;
+        let bucket = self
             .events
-            .entry(execute_time)
-            .or_insert_with(|| Vec::new());
-        let result = events.len();
-        events.push(Some(call_back));
-        result
+            .entry(execute_time.clone())
+            .or_insert_with(Bucket::new);
+        let slot = bucket.insert(generation, call_back);
+        Timeout { time: execute_time, slot, generation }
     }
 
     pub fn enqueue_relative<Es, D>(
         self: &mut Self,
         event: Es,
         execute_delay: D,
-    ) -> usize
+    ) -> Timeout<T>
         where Es: Into<E>,
               T: ops::Add<D, Output=T>,
     {
@@ -134,12 +251,32 @@ impl<E, T> EventQueue<E, T>
         self.enqueue_absolute(event, execute_time)
     }
 
-    pub fn cancel_event(self: &mut Self, execute_time: &T, id: usize) {
-        if let Some(events) = self.events.get_mut(execute_time) {
-            if let Some(event) = events.get_mut(id) {
-                event.take();
+    /// cancels a previously scheduled event; returns whether an event was
+    /// actually removed (it may have already fired, or been cancelled
+    /// before).
+    pub fn cancel(self: &mut Self, timeout: Timeout<T>) -> bool {
+        let removed = match self.events.get_mut(&timeout.time) {
+            Some(bucket) => bucket.cancel(timeout.slot, timeout.generation),
+            None => false,
+        };
+        if removed {
+            let now_empty = self.events
+                .get(&timeout.time)
+                .map_or(false, Bucket::is_empty);
+            if now_empty {
+                self.events.remove(&timeout.time);
             }
         }
+        removed
+    }
+
+    /// true if the event this handle names hasn't fired or been
+    /// cancelled yet
+    pub fn is_pending(self: &Self, timeout: &Timeout<T>) -> bool {
+        self.events
+            .get(&timeout.time)
+            .and_then(|bucket| bucket.slot_generation(timeout.slot))
+            == Some(timeout.generation)
     }
 
     /// progresses in-game time to the next event,
@@ -160,6 +297,42 @@ impl<E, T> EventQueue<E, T>
     }
 }
 
+impl<E, T> EventSource<E, T> for EventQueue<E, T>
+    where T: Ord + Clone
+{
+    fn now(self: &Self) -> T {
+        EventQueue::now(self)
+    }
+
+    fn soonest(self: &Self) -> Option<T> {
+        EventQueue::soonest(self)
+    }
+
+    fn is_empty(self: &Self) -> bool {
+        EventQueue::is_empty(self)
+    }
+
+    fn take_soonest(self: &mut Self) -> Vec<E> {
+        let soonest = match EventQueue::soonest(self) {
+            Some(soonest) => soonest,
+            None => return Vec::new(),
+        };
+
+        // second unwrap should be justified unless `soonest()` misbehaves
+        let bucket = self.events.remove(&soonest).unwrap();
+
+        if self.now < soonest {
+            self.now = soonest;
+        }
+
+        bucket.into_events()
+    }
+
+    fn set_now(self: &mut Self, time: T) {
+        self.now = time;
+    }
+}
+
 impl<G, T> PolyEventQueue<G, T>
     where T: Ord + Clone
 {
@@ -183,42 +356,123 @@ impl<G, T> PolyEventQueue<G, T>
     {
         self.enqueue_relative(EventBox(Box::new(event)), execute_delay);
     }
+
+    /// schedules `factory` to produce a new event every `period`, starting
+    /// at `first_at`, until the returned handle is
+    /// [cancelled](struct.IntervalHandle.html#method.cancel).
+    ///
+    /// scheduling is drift-free: each occurrence is scheduled off the
+    /// previous occurrence's due time, not off the time it actually fired,
+    /// so a busy queue delaying one tick doesn't push later ticks back.
+    pub fn enqueue_interval<F, Es>(
+        self: &mut Self,
+        factory: F,
+        period: Diff<T>,
+        first_at: T,
+    ) -> IntervalHandle
+        where F: 'static + FnMut() -> Es,
+              Es: 'static + Event<G>,
+              G: AsMut<PolyEventQueue<G, T>>,
+              T: 'static + ops::Sub + ops::Add<Diff<T>, Output=T>,
+              Diff<T>: Clone,
+    {
+        let cancelled = Rc::new(Cell::new(false));
+        let wrapper = Interval {
+            factory,
+            period,
+            this_due: first_at.clone(),
+            cancelled: cancelled.clone(),
+            _marker: marker::PhantomData,
+        };
+        self.enqueue_box_absolute(wrapper, first_at);
+        IntervalHandle { cancelled }
+    }
+}
+
+/// cancels a recurring event scheduled via
+/// [enqueue_interval](struct.EventQueue.html#method.enqueue_interval)
+#[derive(Clone)]
+pub struct IntervalHandle {
+    cancelled: Rc<Cell<bool>>,
 }
 
-pub trait Simulation<E, T>
-    where Self: Sized + AsMut<EventQueue<E, T>>,
+impl IntervalHandle {
+    pub fn cancel(self: &Self) {
+        self.cancelled.set(true);
+    }
+
+    pub fn is_cancelled(self: &Self) -> bool {
+        self.cancelled.get()
+    }
+}
+
+// the event actually sitting in the queue for an interval: produces the
+// next payload event, re-enqueues itself for the next occurrence, then
+// hands the payload off to be invoked
+struct Interval<F, Es, T>
+    where T: ops::Sub,
+{
+    factory: F,
+    period: Diff<T>,
+    this_due: T,
+    cancelled: Rc<Cell<bool>>,
+    _marker: marker::PhantomData<Es>,
+}
+
+impl<G, F, Es, T> Event<G> for Interval<F, Es, T>
+    where F: 'static + FnMut() -> Es,
+          Es: 'static + Event<G>,
+          G: AsMut<PolyEventQueue<G, T>>,
+          T: 'static + Ord + Clone + ops::Sub + ops::Add<Diff<T>, Output=T>,
+          Diff<T>: Clone,
+{
+    fn invoke(mut self: Self, game: &mut G) {
+        if self.cancelled.get() {
+            return;
+        }
+
+        let payload = (self.factory)();
+
+        let next_due = self.this_due.clone() + self.period.clone();
+        let next = Interval {
+            factory: self.factory,
+            period: self.period.clone(),
+            this_due: next_due.clone(),
+            cancelled: self.cancelled.clone(),
+            _marker: marker::PhantomData,
+        };
+        game.as_mut().enqueue_box_absolute(next, next_due);
+
+        payload.invoke(game);
+    }
+}
+
+// `Q` defaults to the plain `EventQueue` backend (mirroring the
+// `Event<G, E = EventBox<G>>` default above), and is named directly in the
+// trait reference rather than hidden behind a where-clause: a type
+// parameter that only appears in a bound (`Self: AsMut<Q>`) isn't
+// considered "constrained" by an impl, so without `Q` in the trait ref
+// itself, `impl<G, Q, E, T> Simulation<E, T> for G` wouldn't type-check.
+pub trait Simulation<E, T, Q = EventQueue<E, T>>
+    where Self: Sized + AsMut<Q>,
           T: Ord + Clone,
+          Q: EventSource<E, T>,
           E: GeneralEvent<Self>,
 {
     fn invoke_next(self: &mut Self);
     fn simulate(self: &mut Self, until: T);
 }
 
-impl<G, E, T> Simulation<E, T> for G
-    where G: AsMut<EventQueue<E, T>>,
+impl<G, Q, E, T> Simulation<E, T, Q> for G
+    where G: AsMut<Q>,
+          Q: EventSource<E, T>,
           T: Ord + Clone,
           E: GeneralEvent<G>,
 {
     fn invoke_next(self: &mut Self) {
-        let next_events = {
-            let time = self.as_mut();
-            let soonest = time.soonest();
-            if soonest.is_none() {
-                return
-            }
-            let soonest = soonest.unwrap();
-
-            // second unwrap should be justified unless `soonest()` misbehaves
-            let events = time.events.remove(&soonest).unwrap();
-
-            if time.now < soonest {
-                time.now = soonest;
-            }
+        let next_events = self.as_mut().take_soonest();
 
-            events
-        };
-
-        for event in next_events.into_iter().flat_map(|x| x) {
+        for event in next_events {
             event.invoke(self);
         }
     }
@@ -227,7 +481,101 @@ impl<G, E, T> Simulation<E, T> for G
         while self.as_mut().has_event_by(&until) {
             self.invoke_next();
         }
-        self.as_mut().now = until;
+        self.as_mut().set_now(until);
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_handle_does_not_cancel_an_unrelated_event_at_the_same_time() {
+        let mut q: EventQueue<&'static str, i32> = EventQueue::new(0);
+
+        let a = q.enqueue_absolute("a", 5);
+        // firing drops the bucket `a` was issued from entirely
+        assert_eq!(EventSource::take_soonest(&mut q), vec!["a"]);
+
+        // a new event lands on the same absolute time, in a brand new
+        // bucket that, pre-fix, would restart its generation count at 0
+        q.enqueue_absolute("b", 5);
+
+        // `a`'s handle is stale and must not cancel `b`, even though it
+        // may now name the very same (time, slot) pair
+        assert_eq!(q.cancel(a), false);
+        assert_eq!(EventSource::take_soonest(&mut q), vec!["b"]);
+    }
+
+    struct IntervalGame {
+        queue: PolyEventQueue<IntervalGame, i32>,
+        fired_at: Vec<i32>,
+    }
+
+    impl AsMut<PolyEventQueue<IntervalGame, i32>> for IntervalGame {
+        fn as_mut(self: &mut Self) -> &mut PolyEventQueue<IntervalGame, i32> {
+            &mut self.queue
+        }
+    }
+
+    struct RecordTick;
+
+    impl Event<IntervalGame> for RecordTick {
+        fn invoke(self: Self, game: &mut IntervalGame) {
+            let now = game.queue.now();
+            game.fired_at.push(now);
+        }
+    }
+
+    #[test]
+    fn enqueue_interval_reschedules_off_due_time_not_actual_fire_time() {
+        let mut game = IntervalGame {
+            queue: EventQueue::new(0),
+            fired_at: Vec::new(),
+        };
+        game.queue.enqueue_interval(|| RecordTick, 10, 0);
+        assert_eq!(game.queue.soonest(), Some(0));
+
+        // skip straight to 25 without firing anything, simulating a busy
+        // queue that falls behind schedule
+        EventSource::set_now(&mut game.queue, 25);
+
+        // each occurrence still reschedules onto the original period
+        // grid (0, 10, 20, ...) rather than being re-anchored off 25,
+        // the time it actually got around to firing (a drifting
+        // implementation would instead land on 35, 45, 55, ...)
+        for expected_next_due in &[10, 20, 30] {
+            for event in EventSource::take_soonest(&mut game.queue) {
+                event.invoke(&mut game);
+            }
+            assert_eq!(game.queue.soonest(), Some(*expected_next_due));
+        }
+        assert_eq!(game.fired_at.len(), 3);
+    }
+
+    #[test]
+    fn enqueue_interval_handle_cancel_stops_future_firings() {
+        let mut game = IntervalGame {
+            queue: EventQueue::new(0),
+            fired_at: Vec::new(),
+        };
+        let handle = game.queue.enqueue_interval(|| RecordTick, 10, 0);
+
+        for event in EventSource::take_soonest(&mut game.queue) {
+            event.invoke(&mut game);
+        }
+        assert_eq!(game.fired_at, vec![0]);
+
+        handle.cancel();
+
+        // the occurrence already scheduled by the first firing (due at
+        // 10) still gets dequeued, but finds itself cancelled and
+        // neither fires its payload nor schedules another occurrence
+        assert!(!game.queue.is_empty());
+        for event in EventSource::take_soonest(&mut game.queue) {
+            event.invoke(&mut game);
+        }
+        assert_eq!(game.fired_at, vec![0]);
+        assert!(game.queue.is_empty());
+    }
+}
@@ -91,6 +91,46 @@ impl<C, I, G> Server<C, I, G>
         }
         self.clock.end_cycles();
     }
+
+    /// like [run](#method.run), but never sleeps or blocks on the wall
+    /// clock: drains every due event and every external interruption
+    /// already sent on the channel, then returns as soon as both are
+    /// exhausted.
+    ///
+    /// Intended for a `MockClock`-backed `Server` in tests: advance the
+    /// clock, push inputs, then call this to bring the simulation up to
+    /// date before asserting on it. Returns true if an interruption told
+    /// the server to stop.
+    pub fn run_until_idle<E, T>(self: &mut Self) -> bool
+        where C: Clock<T>,
+              G: event_queue::Simulation<E, T>,
+              E: event_queue::GeneralEvent<G>,
+              T: Ord + Clone,
+    {
+        // the clock is assumed not to care about `now` here (that's the
+        // point of a mock clock); it only needs some instant to pass in
+        let dummy_now = time::Instant::now();
+        loop {
+            let in_game = self.clock.in_game(dummy_now);
+            if let Ok(upd) = self.external.try_recv() {
+                self.clock.finished_cycle(dummy_now, in_game.clone());
+                if apply_update(&mut self.game, upd, in_game) {
+                    return true;
+                }
+            } else if let Some(et) = self.game.as_mut().soonest() {
+                if et <= in_game {
+                    self.clock.finished_cycle(dummy_now, et);
+                    self.game.invoke_next();
+                } else {
+                    self.clock.end_cycles();
+                    return false;
+                }
+            } else {
+                self.clock.end_cycles();
+                return false;
+            }
+        }
+    }
 }
 
 pub trait Interruption<G> {
@@ -130,3 +170,86 @@ pub trait Clock<T> where T: Ord {
     );
 }
 
+#[cfg(test)]
+mod tests {
+    use std::ops;
+    use std::sync::mpsc;
+    use std::time;
+
+    use clock::MockClock;
+    use event_queue::{EventQueue, GeneralEvent};
+
+    use super::{Interruption, Server};
+
+    #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+    struct TestTime(u64);
+
+    struct TestDuration(u64);
+
+    impl ops::Sub for TestTime {
+        type Output = TestDuration;
+        fn sub(self: TestTime, other: TestTime) -> TestDuration {
+            TestDuration(self.0 - other.0)
+        }
+    }
+
+    impl From<TestDuration> for time::Duration {
+        fn from(d: TestDuration) -> time::Duration {
+            time::Duration::from_secs(d.0)
+        }
+    }
+
+    struct Game {
+        queue: EventQueue<&'static str, TestTime>,
+        log: Vec<&'static str>,
+    }
+
+    impl AsMut<EventQueue<&'static str, TestTime>> for Game {
+        fn as_mut(self: &mut Self) -> &mut EventQueue<&'static str, TestTime> {
+            &mut self.queue
+        }
+    }
+
+    impl GeneralEvent<Game> for &'static str {
+        fn invoke(self: Self, game: &mut Game) {
+            game.log.push(self);
+        }
+    }
+
+    struct Push(&'static str);
+
+    impl Interruption<Game> for Push {
+        fn update(self: Self, game: &mut Game) -> bool {
+            game.log.push(self.0);
+            false
+        }
+    }
+
+    #[test]
+    fn run_until_idle_is_deterministic_under_a_mock_clock() {
+        let mut game = Game { queue: EventQueue::new(TestTime(0)), log: Vec::new() };
+        game.queue.enqueue_absolute("due-at-5", TestTime(5));
+        game.queue.enqueue_absolute("due-at-10", TestTime(10));
+
+        let (sender, receiver) = mpsc::channel();
+        let clock = MockClock::new(TestTime(0));
+        let mut server = Server::new(game, receiver, clock);
+
+        // nothing is due yet, and no input has been sent
+        assert_eq!(server.run_until_idle(), false);
+        assert_eq!(server.game.log, Vec::<&str>::new());
+
+        // advancing the mock clock (not the wall clock) is what makes the
+        // due-at-5 event fire
+        server.clock.advance_to(TestTime(5));
+        assert_eq!(server.run_until_idle(), false);
+        assert_eq!(server.game.log, vec!["due-at-5"]);
+
+        // an input sent before the next advance is applied before the
+        // event it happens to land alongside
+        sender.send(Push("input")).unwrap();
+        server.clock.advance_to(TestTime(10));
+        assert_eq!(server.run_until_idle(), false);
+        assert_eq!(server.game.log, vec!["due-at-5", "input", "due-at-10"]);
+    }
+}
@@ -0,0 +1,414 @@
+use std::collections;
+use std::ops;
+
+use event_queue::EventSource;
+
+type Diff<T> = <T as ops::Sub>::Output;
+
+// entries further out than this many wheel revolutions are kept in
+// `overflow` instead, so a timeout scheduled far in the future doesn't sit
+// in a slot uselessly decrementing its rotation count for a very long time
+const MAX_ROTATIONS: u64 = 1 << 12;
+
+// an event sitting in a wheel slot, waiting for the wheel to spin around to
+// it `rotations` more times before it is actually due
+struct Entry<E, T> {
+    due: T,
+    rotations: u64,
+    event: E,
+}
+
+/// A hashed-timing-wheel backed [EventSource](../event_queue/trait.EventSource.html),
+/// usable anywhere an [EventQueue](../event_queue/struct.EventQueue.html)
+/// is, via the same [Simulation](../event_queue/trait.Simulation.html)
+/// blanket impl (implement `AsMut<WheelEventQueue<E, T>>` for your game
+/// type instead of `AsMut<EventQueue<E, T>>`).
+///
+/// Events are bucketed by `floor((due - start) / tick) mod slots.len()`,
+/// together with a "rotations" counter recording how many more full laps
+/// of the wheel must pass before the event is actually due. Advancing the
+/// clock walks forward slot by slot, decrementing rotations and firing
+/// entries that hit zero, so enqueueing and expiring near-future events is
+/// amortized O(1) rather than the O(log n) of a `BTreeMap`. Entries that
+/// would need more than `MAX_ROTATIONS` laps are kept in an `overflow` map
+/// instead, and are moved back onto the wheel once they come into range.
+pub struct WheelEventQueue<E, T>
+    where T: Ord + Clone + ops::Sub,
+          Diff<T>: Clone + Into<f64>,
+{
+    now: T,
+    start: T,
+    tick: Diff<T>,
+    cursor: usize,
+    slots: Vec<Vec<Entry<E, T>>>,
+    overflow: collections::BTreeMap<T, Vec<E>>,
+}
+
+impl<E, T> WheelEventQueue<E, T>
+    where T: Ord + Clone + ops::Sub + ops::Add<Diff<T>, Output=T>,
+          Diff<T>: Clone + Into<f64>,
+{
+    /// `slot_count` must be a power of two.
+    pub fn new(initial_time: T, tick: Diff<T>, slot_count: usize) -> Self {
+        assert!(
+            slot_count.is_power_of_two(),
+            "slot_count must be a power of two",
+        );
+        WheelEventQueue {
+            now: initial_time.clone(),
+            start: initial_time,
+            tick,
+            cursor: 0,
+            slots: (0..slot_count).map(|_| Vec::new()).collect(),
+            overflow: collections::BTreeMap::new(),
+        }
+    }
+
+    fn mask(self: &Self) -> usize {
+        self.slots.len() - 1
+    }
+
+    fn tick_of(self: &Self, time: &T) -> u64 {
+        let elapsed: Diff<T> = time.clone() - self.start.clone();
+        let ticks: f64 = elapsed.into() / self.tick.clone().into();
+        if ticks < 0.0 { 0 } else { ticks as u64 }
+    }
+
+    fn current_tick(self: &Self) -> u64 {
+        self.tick_of(&self.now.clone())
+    }
+
+    pub fn now(self: &Self) -> T {
+        self.now.clone()
+    }
+
+    pub fn is_empty(self: &Self) -> bool {
+        self.overflow.is_empty() && self.slots.iter().all(Vec::is_empty)
+    }
+
+    /// the nearest due time: walks forward from the cursor (at most one
+    /// full rotation) to the first slot holding an entry due on this lap,
+    /// falls back to the earliest entry still waiting out laps if there is
+    /// none, and compares that against the overflow's front
+    pub fn soonest(self: &Self) -> Option<T> {
+        let mut wheel_soonest = None;
+        for step in 0..self.slots.len() {
+            let idx = (self.cursor + step) & self.mask();
+            let due_this_lap = self.slots[idx].iter()
+                .filter(|e| e.rotations == 0)
+                .map(|e| e.due.clone())
+                .min();
+            if let Some(due) = due_this_lap {
+                wheel_soonest = Some(due);
+                break;
+            }
+        }
+        if wheel_soonest.is_none() {
+            wheel_soonest = self.slots.iter()
+                .flat_map(|slot| slot.iter())
+                .map(|e| e.due.clone())
+                .min();
+        }
+        let overflow_soonest = self.overflow.keys().next().map(Clone::clone);
+        match (wheel_soonest, overflow_soonest) {
+            (Some(a), Some(b)) => Some(if a <= b { a } else { b }),
+            (a, None) => a,
+            (None, b) => b,
+        }
+    }
+
+    fn place_on_wheel(self: &mut Self, due: T, event: E) {
+        let due_tick = self.tick_of(&due);
+        let current = self.current_tick();
+        if due_tick <= current {
+            // already due (or due on this very tick): land directly in
+            // the slot the cursor is sitting on, so it fires on the next
+            // step regardless of what `due_tick mod slots.len()` hashes
+            // to - otherwise it could sit behind the cursor and have to
+            // wait almost a full rotation to be reached
+            let slot = self.cursor & self.mask();
+            self.slots[slot].push(Entry { due, rotations: 0, event });
+            return;
+        }
+
+        let offset = due_tick - current;
+        let span = self.slots.len() as u64;
+        let rotations = offset / span;
+        if rotations > MAX_ROTATIONS {
+            self.overflow.entry(due).or_insert_with(Vec::new).push(event);
+        } else {
+            let slot = (due_tick as usize) & self.mask();
+            self.slots[slot].push(Entry { due, rotations, event });
+        }
+    }
+
+    pub fn enqueue_absolute<Es>(self: &mut Self, event: Es, execute_time: T)
+        where Es: Into<E>,
+    {
+        self.place_on_wheel(execute_time, event.into());
+    }
+
+    pub fn enqueue_relative<Es, D>(self: &mut Self, event: Es, execute_delay: D)
+        where Es: Into<E>,
+              T: ops::Add<D, Output=T>,
+    {
+        let execute_time = self.now() + execute_delay;
+        self.enqueue_absolute(event, execute_time);
+    }
+
+    // looks at the slot the cursor is currently sitting on: if anything
+    // there is due, fires it without moving the wheel any further
+    // (mirroring `EventQueue::take_soonest`, which only ever advances
+    // `now` up to the event it actually fires); otherwise advances the
+    // wheel by one tick and tries the next slot
+    fn step(self: &mut Self) -> Vec<E> {
+        let slot = self.cursor & self.mask();
+        let entries = self.slots[slot].drain(..).collect::<Vec<_>>();
+        let (due, pending): (Vec<_>, Vec<_>) =
+            entries.into_iter().partition(|e| e.rotations == 0);
+
+        if !due.is_empty() {
+            // the wheel stays on this slot, so entries still waiting out
+            // laps keep their counts until the wheel actually moves past it
+            self.slots[slot] = pending;
+            let latest_due = due.iter().map(|e| e.due.clone()).max().unwrap();
+            if self.now < latest_due {
+                self.now = latest_due;
+            }
+            return due.into_iter().map(|e| e.event).collect();
+        }
+
+        self.slots[slot] = pending
+            .into_iter()
+            .map(|entry| Entry { rotations: entry.rotations - 1, ..entry })
+            .collect();
+        self.cursor = (self.cursor + 1) & self.mask();
+        self.now = self.now.clone() + self.tick.clone();
+
+        if self.cursor == 0 {
+            // completed a revolution: anything in overflow that is now
+            // within MAX_ROTATIONS of the wheel can come back on
+            let ready: Vec<T> = self.overflow.keys().cloned()
+                .filter(|t| {
+                    let offset = self.tick_of(t).saturating_sub(self.current_tick());
+                    offset / (self.slots.len() as u64) <= MAX_ROTATIONS
+                })
+                .collect();
+            for due_time in ready {
+                if let Some(events) = self.overflow.remove(&due_time) {
+                    for event in events {
+                        self.place_on_wheel(due_time.clone(), event);
+                    }
+                }
+            }
+        }
+
+        Vec::new()
+    }
+}
+
+impl<E, T> EventSource<E, T> for WheelEventQueue<E, T>
+    where T: Ord + Clone + ops::Sub + ops::Add<Diff<T>, Output=T>,
+          Diff<T>: Clone + Into<f64>,
+{
+    fn now(self: &Self) -> T {
+        WheelEventQueue::now(self)
+    }
+
+    fn soonest(self: &Self) -> Option<T> {
+        WheelEventQueue::soonest(self)
+    }
+
+    fn is_empty(self: &Self) -> bool {
+        WheelEventQueue::is_empty(self)
+    }
+
+    fn take_soonest(self: &mut Self) -> Vec<E> {
+        if WheelEventQueue::is_empty(self) {
+            return Vec::new();
+        }
+        loop {
+            let due = self.step();
+            if !due.is_empty() {
+                return due;
+            }
+        }
+    }
+
+    fn set_now(self: &mut Self, time: T) {
+        // skip straight to `time` without firing anything; used when the
+        // caller just wants to fast-forward past a dry spell. Computes
+        // the cursor/rotation delta analytically (the same tick math
+        // `tick_of`/`place_on_wheel` already use) instead of calling
+        // `step` once per tick, so this is O(slots.len()) in the gap
+        // being skipped rather than O((time - now) / tick).
+        if self.now >= time {
+            return;
+        }
+
+        let target_tick = self.tick_of(&time);
+        let current = self.current_tick();
+        let elapsed = target_tick.saturating_sub(current);
+        let span = self.slots.len() as u64;
+        let full_rotations = elapsed / span;
+        let remainder = elapsed % span;
+
+        for (i, slot) in self.slots.iter_mut().enumerate() {
+            // how many times this slot would be visited while stepping
+            // forward one tick at a time from the cursor to `target_tick`
+            let forward_dist = (i as u64 + span - (self.cursor as u64)) % span;
+            let visits = full_rotations + if forward_dist < remainder { 1 } else { 0 };
+            if visits > 0 {
+                for entry in slot.iter_mut() {
+                    entry.rotations = entry.rotations.saturating_sub(visits);
+                }
+            }
+        }
+
+        self.cursor = ((self.cursor as u64 + elapsed) as usize) & self.mask();
+        self.now = time;
+
+        if elapsed > 0 {
+            // we may have crossed one or more full revolutions: give
+            // overflow the same chance to migrate back onto the wheel
+            // that a completed lap in `step` would have given it
+            let ready: Vec<T> = self.overflow.keys().cloned()
+                .filter(|t| {
+                    let offset = self.tick_of(t).saturating_sub(self.current_tick());
+                    offset / span <= MAX_ROTATIONS
+                })
+                .collect();
+            for due_time in ready {
+                if let Some(events) = self.overflow.remove(&due_time) {
+                    for event in events {
+                        self.place_on_wheel(due_time.clone(), event);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp;
+    use std::ops;
+
+    use event_queue::EventSource;
+
+    use super::WheelEventQueue;
+
+    #[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+    struct TestTime(f64);
+
+    impl Eq for TestTime {}
+
+    impl Ord for TestTime {
+        fn cmp(self: &Self, other: &Self) -> cmp::Ordering {
+            self.partial_cmp(other).expect("NaN TestTime in test")
+        }
+    }
+
+    impl ops::Sub for TestTime {
+        type Output = TestTime;
+        fn sub(self: TestTime, other: TestTime) -> TestTime {
+            TestTime(self.0 - other.0)
+        }
+    }
+
+    impl ops::Add for TestTime {
+        type Output = TestTime;
+        fn add(self: TestTime, other: TestTime) -> TestTime {
+            TestTime(self.0 + other.0)
+        }
+    }
+
+    impl Into<f64> for TestTime {
+        fn into(self: Self) -> f64 {
+            self.0
+        }
+    }
+
+    fn wheel() -> WheelEventQueue<&'static str, TestTime> {
+        WheelEventQueue::new(TestTime(0.0), TestTime(1.0), 8)
+    }
+
+    #[test]
+    fn fires_events_in_due_order() {
+        let mut q = wheel();
+        q.enqueue_absolute("second", TestTime(2.0));
+        q.enqueue_absolute("first", TestTime(1.0));
+
+        assert_eq!(EventSource::take_soonest(&mut q), vec!["first"]);
+        assert_eq!(q.now(), TestTime(1.0));
+
+        assert_eq!(EventSource::take_soonest(&mut q), vec!["second"]);
+        assert_eq!(q.now(), TestTime(2.0));
+    }
+
+    #[test]
+    fn already_due_event_fires_without_overshooting_now() {
+        let mut q = wheel();
+        // fast-forward with nothing scheduled yet
+        EventSource::set_now(&mut q, TestTime(100.0));
+
+        // scheduled for the past relative to `now` (e.g. a zero-delay
+        // follow-up event enqueued from inside another event's invoke)
+        q.enqueue_absolute("late", TestTime(3.0));
+
+        assert_eq!(EventSource::take_soonest(&mut q), vec!["late"]);
+        // firing an overdue event must not drag `now` forward
+        assert_eq!(q.now(), TestTime(100.0));
+    }
+
+    #[test]
+    fn empty_queue_has_no_soonest() {
+        let q = wheel();
+        assert!(q.is_empty());
+        assert_eq!(q.soonest(), None);
+    }
+
+    #[test]
+    fn multi_rotation_entry_fires_after_full_laps() {
+        let mut q = wheel();
+        // 8 slots: due tick 10 needs the wheel to come all the way
+        // around to slot 2 a second time before it's actually due
+        q.enqueue_absolute("far", TestTime(10.0));
+
+        assert_eq!(EventSource::take_soonest(&mut q), vec!["far"]);
+        assert_eq!(q.now(), TestTime(10.0));
+    }
+
+    #[test]
+    fn set_now_bulk_advances_rotation_counters() {
+        let mut q = wheel();
+        // due tick 19 needs 2 full laps (16 ticks) plus 3 more
+        q.enqueue_absolute("far", TestTime(19.0));
+
+        // jump straight past both laps in one call instead of walking
+        // there tick by tick
+        EventSource::set_now(&mut q, TestTime(16.0));
+        assert_eq!(q.now(), TestTime(16.0));
+
+        // only the remaining 3 ticks are left to step through
+        assert_eq!(EventSource::take_soonest(&mut q), vec!["far"]);
+        assert_eq!(q.now(), TestTime(19.0));
+    }
+
+    #[test]
+    fn entries_past_max_rotations_spill_to_overflow_and_later_fire() {
+        let mut q = wheel();
+        let due = TestTime(8.0 * (super::MAX_ROTATIONS as f64 + 5.0));
+        q.enqueue_absolute("distant", due);
+
+        // too many rotations out to fit on the wheel: parked in overflow
+        assert!(q.is_empty() == false);
+        assert_eq!(q.soonest(), Some(due));
+
+        // fast-forward to just shy of it; within `MAX_ROTATIONS` of the
+        // wheel now, so it should have migrated back on by this point
+        EventSource::set_now(&mut q, TestTime(due.0 - 1.0));
+        assert_eq!(EventSource::take_soonest(&mut q), vec!["distant"]);
+        assert_eq!(q.now(), due);
+    }
+}